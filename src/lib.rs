@@ -1,11 +1,16 @@
 #![allow(missing_docs)]
 
 extern crate bodyparser;
+extern crate chacha20poly1305;
+extern crate hmac;
+extern crate pbkdf2;
+extern crate sha2;
 #[macro_use]
 extern crate exonum;
 #[macro_use]
 extern crate failure;
 extern crate iron;
+extern crate rand;
 extern crate router;
 extern crate serde;
 #[macro_use]
@@ -14,32 +19,81 @@ extern crate serde_json;
 
 
 pub mod schema {
-    use exonum::storage::{Fork, MapIndex, Snapshot};
-    use exonum::crypto::PublicKey;
+    use exonum::storage::{Entry, Fork, ProofListIndex, ProofMapIndex, Snapshot};
+    use exonum::crypto::{self, Hash, PublicKey};
+
+    // Asset id used by the original single-currency transactions
+    // (`TxTransfer`, `TxIssue`, `TxLock`/`TxClaim`/`TxRefund`).
+    pub const BASE_ASSET: &str = "EXO";
+
+    // Key into `wallet_balances`: a wallet only ever has one balance per
+    // asset, so the pair is folded into a single `Hash` key rather than a
+    // tuple, keeping the table a plain `ProofMapIndex<Hash, u64>` whose root
+    // feeds into `CurrencySchema::state_hash`.
+    pub fn balance_key(pub_key: &PublicKey, asset_id: &str) -> Hash {
+        let mut bytes = pub_key.as_ref().to_vec();
+        bytes.extend_from_slice(asset_id.as_bytes());
+        crypto::hash(&bytes)
+    }
 
     encoding_struct! {
         struct Wallet {
             pub_key: &PublicKey,
             name: &str,
             email: &str,
-            balance: u64,
+            history_len: u64,
+            history_hash: &Hash,
         }
     }
 
     impl Wallet {
-        pub fn increase(self, amount: u64) -> Self {
-            let balance = self.balance() + amount;
-            Self::new(self.pub_key(), self.name(), self.email(), balance)
+        // Returns a copy of this wallet with `history_len`/`history_hash` refreshed
+        // to match the wallet's `wallet_history` list after a new entry was pushed onto it.
+        pub fn grow_history(self, history_len: u64, history_hash: &Hash) -> Self {
+            Self::new(
+                self.pub_key(),
+                self.name(),
+                self.email(),
+                history_len,
+                history_hash,
+            )
+        }
+    }
+
+    encoding_struct! {
+        // An amount locked by `TxLock`, claimable by `to` with a preimage of
+        // `hashlock`, or refundable to `from` once `deadline_height` has passed.
+        struct LockEntry {
+            from: &PublicKey,
+            to: &PublicKey,
+            amount: u64,
+            hashlock: &Hash,
+            deadline_height: u64,
         }
+    }
 
-        pub fn decrease(self, amount: u64) -> Self {
-            let balance = self.balance() - amount;
-            Self::new(self.pub_key(), self.name(), self.email(), balance)
+    encoding_struct! {
+        // Faucet withdrawal ceiling, written once by `CurrencyService::initialize`.
+        // `limit` is expressed in whole tokens with `denomination` decimal places,
+        // e.g. `limit = 5, denomination = 2` permits withdrawing up to 500 base units.
+        struct FaucetConfig {
+            limit: u64,
+            denomination: u8,
         }
+    }
 
-        pub fn freeze(self, amount: u64) -> Self {
-            let balance = self.balance() - amount;
-            Self::new(self.pub_key(), self.name(), self.email(), balance)
+    impl FaucetConfig {
+        // Returns the withdrawal ceiling in base units (`limit * 10^denomination`).
+        pub fn ceiling(&self) -> u64 {
+            self.limit() * 10u64.pow(u32::from(self.denomination()))
+        }
+    }
+
+    encoding_struct! {
+        // The one key allowed to mint new tokens via `TxIssue`, written once by
+        // `CurrencyService::initialize`.
+        struct IssuerConfig {
+            issuer_key: &PublicKey,
         }
     }
 
@@ -52,25 +106,175 @@ pub mod schema {
             CurrencySchema { view }
         }
 
-        pub fn wallets(&self) -> MapIndex<&Snapshot, PublicKey, Wallet> {
-            MapIndex::new("cryptocurrency.wallets", self.view.as_ref())
+        pub fn wallets(&self) -> ProofMapIndex<&Snapshot, PublicKey, Wallet> {
+            ProofMapIndex::new("cryptocurrency.wallets", self.view.as_ref())
         }
 
         // Utility method to quickly get a separate wallet from the storage
         pub fn wallet(&self, pub_key: &PublicKey) -> Option<Wallet> {
             self.wallets().get(pub_key)
         }
+
+        // Returns the list of transaction hashes that have touched the given wallet.
+        pub fn wallet_history(&self, pub_key: &PublicKey) -> ProofListIndex<&Snapshot, Hash> {
+            ProofListIndex::new_in_family(
+                "cryptocurrency.wallet_history",
+                pub_key,
+                self.view.as_ref(),
+            )
+        }
+
+        // HTLC locks, keyed by hashlock.
+        pub fn locks(&self) -> ProofMapIndex<&Snapshot, Hash, LockEntry> {
+            ProofMapIndex::new("cryptocurrency.locks", self.view.as_ref())
+        }
+
+        pub fn lock(&self, hashlock: &Hash) -> Option<LockEntry> {
+            self.locks().get(hashlock)
+        }
+
+        // Per-asset wallet balances, keyed by `balance_key(pub_key, asset_id)`.
+        // A `ProofMapIndex` so balances are folded into `state_hash` and can be
+        // proved over the API, the same way `wallets` is.
+        pub fn wallet_balances(&self) -> ProofMapIndex<&Snapshot, Hash, u64> {
+            ProofMapIndex::new("cryptocurrency.wallet_balances", self.view.as_ref())
+        }
+
+        // Returns the wallet's balance of a single asset, or 0 if it never held any.
+        pub fn wallet_balance(&self, pub_key: &PublicKey, asset_id: &str) -> u64 {
+            self.wallet_balances()
+                .get(&balance_key(pub_key, asset_id))
+                .unwrap_or(0)
+        }
+
+        // Asset ids a wallet has ever held a nonzero balance of. `wallet_balances`
+        // is keyed by an opaque hash so it can't be scanned by wallet; this list
+        // is what lets the API enumerate a wallet's balances.
+        pub fn wallet_assets(&self, pub_key: &PublicKey) -> ProofListIndex<&Snapshot, String> {
+            ProofListIndex::new_in_family(
+                "cryptocurrency.wallet_assets",
+                pub_key,
+                self.view.as_ref(),
+            )
+        }
+
+        // Faucet withdrawal ceiling, written by `CurrencyService::initialize`.
+        pub fn faucet_config(&self) -> Entry<&Snapshot, FaucetConfig> {
+            Entry::new("cryptocurrency.faucet_config", self.view.as_ref())
+        }
+
+        // Cumulative amount each wallet has withdrawn from the faucet, in base units.
+        pub fn faucet_withdrawals(&self) -> ProofMapIndex<&Snapshot, PublicKey, u64> {
+            ProofMapIndex::new("cryptocurrency.faucet_withdrawals", self.view.as_ref())
+        }
+
+        pub fn faucet_withdrawn(&self, pub_key: &PublicKey) -> u64 {
+            self.faucet_withdrawals().get(pub_key).unwrap_or(0)
+        }
+
+        // The one key allowed to mint new tokens, written by `CurrencyService::initialize`.
+        pub fn issuer_config(&self) -> Entry<&Snapshot, IssuerConfig> {
+            Entry::new("cryptocurrency.issuer_config", self.view.as_ref())
+        }
+
+        // Returns the root hashes of the wallets and balances tables, used by
+        // `Service::state_hash`.
+        pub fn state_hash(&self) -> Vec<Hash> {
+            vec![self.wallets().merkle_root(), self.wallet_balances().merkle_root()]
+        }
     }
 
     impl<'a> CurrencySchema<&'a mut Fork> {
-        pub fn wallets_mut(&mut self) -> MapIndex<&mut Fork, PublicKey, Wallet> {
-            MapIndex::new("cryptocurrency.wallets", &mut self.view)
+        pub fn wallets_mut(&mut self) -> ProofMapIndex<&mut Fork, PublicKey, Wallet> {
+            ProofMapIndex::new("cryptocurrency.wallets", &mut self.view)
+        }
+
+        pub fn wallet_history_mut(
+            &mut self,
+            pub_key: &PublicKey,
+        ) -> ProofListIndex<&mut Fork, Hash> {
+            ProofListIndex::new_in_family(
+                "cryptocurrency.wallet_history",
+                pub_key,
+                &mut self.view,
+            )
+        }
+
+        // Appends `tx_hash` to the wallet's history and returns the wallet with its
+        // `history_len`/`history_hash` refreshed to match.
+        pub fn append_history(&mut self, pub_key: &PublicKey, wallet: Wallet, tx_hash: &Hash) -> Wallet {
+            let mut history = self.wallet_history_mut(pub_key);
+            history.push(*tx_hash);
+            let history_hash = history.merkle_root();
+            let history_len = history.len();
+            wallet.grow_history(history_len, &history_hash)
+        }
+
+        pub fn locks_mut(&mut self) -> ProofMapIndex<&mut Fork, Hash, LockEntry> {
+            ProofMapIndex::new("cryptocurrency.locks", &mut self.view)
+        }
+
+        pub fn wallet_balances_mut(&mut self) -> ProofMapIndex<&mut Fork, Hash, u64> {
+            ProofMapIndex::new("cryptocurrency.wallet_balances", &mut self.view)
+        }
+
+        pub fn wallet_assets_mut(&mut self, pub_key: &PublicKey) -> ProofListIndex<&mut Fork, String> {
+            ProofListIndex::new_in_family(
+                "cryptocurrency.wallet_assets",
+                pub_key,
+                &mut self.view,
+            )
+        }
+
+        // Credits `amount` of `asset_id` to the wallet's balance. The first time a
+        // wallet holds a given asset, its id is recorded in `wallet_assets` so the
+        // API can enumerate balances without scanning the hash-keyed index.
+        pub fn increase_balance(&mut self, pub_key: &PublicKey, asset_id: &str, amount: u64) {
+            let previous = self.wallet_balance(pub_key, asset_id);
+            let already_listed = self.wallet_assets(pub_key).iter().any(|id| id == asset_id);
+            if amount > 0 && !already_listed {
+                self.wallet_assets_mut(pub_key).push(asset_id.to_owned());
+            }
+            let balance = previous + amount;
+            self.wallet_balances_mut().put(&balance_key(pub_key, asset_id), balance);
+        }
+
+        // Debits `amount` of `asset_id` from the wallet's balance.
+        pub fn decrease_balance(&mut self, pub_key: &PublicKey, asset_id: &str, amount: u64) {
+            let balance = self.wallet_balance(pub_key, asset_id) - amount;
+            self.wallet_balances_mut().put(&balance_key(pub_key, asset_id), balance);
+        }
+
+        pub fn faucet_config_mut(&mut self) -> Entry<&mut Fork, FaucetConfig> {
+            Entry::new("cryptocurrency.faucet_config", &mut self.view)
+        }
+
+        pub fn set_faucet_config(&mut self, config: FaucetConfig) {
+            self.faucet_config_mut().set(config);
+        }
+
+        pub fn issuer_config_mut(&mut self) -> Entry<&mut Fork, IssuerConfig> {
+            Entry::new("cryptocurrency.issuer_config", &mut self.view)
+        }
+
+        pub fn set_issuer_config(&mut self, config: IssuerConfig) {
+            self.issuer_config_mut().set(config);
+        }
+
+        pub fn faucet_withdrawals_mut(&mut self) -> ProofMapIndex<&mut Fork, PublicKey, u64> {
+            ProofMapIndex::new("cryptocurrency.faucet_withdrawals", &mut self.view)
+        }
+
+        // Bumps the wallet's cumulative faucet withdrawal by `amount`.
+        pub fn record_faucet_withdrawal(&mut self, pub_key: &PublicKey, amount: u64) {
+            let withdrawn = self.faucet_withdrawn(pub_key) + amount;
+            self.faucet_withdrawals_mut().put(pub_key, withdrawn);
         }
     }
 }
 
 pub mod transactions {
-    use exonum::crypto::PublicKey;
+    use exonum::crypto::{Hash, PublicKey};
 
     use service::SERVICE_ID;
 
@@ -94,9 +298,58 @@ pub mod transactions {
                 seed: u64,
             }
 
-            struct TxFreeze {
+            // Transaction type for minting new tokens into an existing wallet.
+            // Must be signed by the service's configured issuer key, not by the
+            // wallet being credited.
+            struct TxIssue {
+                pub_key: &PublicKey,
+                issuer: &PublicKey,
+                amount: u64,
+                seed: u64,
+            }
+
+            // Converts `from_amount` of `from_asset` held by `from` into `to_asset`
+            // credited to `to`, at a fixed-point rate of `rate_ppm` parts per million
+            // (i.e. `to_amount = from_amount * rate_ppm / 1_000_000`).
+            struct TxTransferExchange {
+                from: &PublicKey,
+                to: &PublicKey,
+                from_asset: &str,
+                to_asset: &str,
+                from_amount: u64,
+                rate_ppm: u64,
+                seed: u64,
+            }
+
+            // Self-service withdrawal from the service's faucet, bounded by the
+            // per-wallet ceiling in `FaucetConfig`.
+            struct TxFaucet {
                 pub_key: &PublicKey,
                 amount: u64,
+                seed: u64,
+            }
+
+            // Moves `amount` out of `from`'s balance into an HTLC escrow keyed by
+            // `hashlock`, refundable to `from` once `deadline_height` passes.
+            struct TxLock {
+                from: &PublicKey,
+                to: &PublicKey,
+                amount: u64,
+                hashlock: &Hash,
+                deadline_height: u64,
+                seed: u64,
+            }
+
+            // Claims a lock by revealing its preimage, crediting `to`.
+            struct TxClaim {
+                to: &PublicKey,
+                hashlock: &Hash,
+                preimage: &[u8],
+            }
+
+            // Returns a lock's funds to its original sender once it has expired.
+            struct TxRefund {
+                hashlock: &Hash,
             }
         }
     }
@@ -119,6 +372,33 @@ pub mod errors {
 
         #[fail(display = "Insufficient currency amount")]
         InsufficientCurrencyAmount = 3,
+
+        #[fail(display = "Lock already exists")]
+        LockAlreadyExists = 4,
+
+        #[fail(display = "Lock not found")]
+        LockNotFound = 5,
+
+        #[fail(display = "Invalid preimage")]
+        InvalidPreimage = 6,
+
+        #[fail(display = "Lock has not expired yet")]
+        LockNotExpired = 7,
+
+        #[fail(display = "Conversion overflow")]
+        ConversionOverflow = 8,
+
+        #[fail(display = "Faucet withdrawal limit exceeded")]
+        FaucetLimitExceeded = 9,
+
+        #[fail(display = "Issuer key does not match the configured issuer")]
+        NotAuthorizedIssuer = 10,
+
+        #[fail(display = "Claimant does not match the lock's intended recipient")]
+        ClaimantMismatch = 11,
+
+        #[fail(display = "Lock has already expired")]
+        LockExpired = 12,
     }
 
     // Conversion between service-specific errors and the standard error type
@@ -132,15 +412,16 @@ pub mod errors {
 }
 
 pub mod contracts {
-    use exonum::blockchain::{ExecutionResult, Transaction};
-    use exonum::{messages::Message, storage::Fork};
+    use exonum::blockchain::{ExecutionResult, Schema as CoreSchema, Transaction};
+    use exonum::{crypto, crypto::Hash, messages::Message, storage::Fork};
 
-    use schema::{CurrencySchema, Wallet};
-    use transactions::{TxCreateWallet, TxTransfer, TxFreeze};
+    use schema::{CurrencySchema, LockEntry, Wallet, BASE_ASSET};
+    use transactions::{
+        TxCreateWallet, TxTransfer, TxIssue, TxLock, TxClaim, TxRefund, TxTransferExchange,
+        TxFaucet,
+    };
     use errors::Error;
 
-    const INIT_BALANCE: u64 = 0;
-
 
     impl Transaction for TxCreateWallet {
         fn verify(&self) -> bool {
@@ -148,9 +429,17 @@ pub mod contracts {
         }
 
         fn execute(&self, view: &mut Fork) -> ExecutionResult {
+            let tx_hash = self.hash();
             let mut schema = CurrencySchema::new(view);
             if schema.wallet(self.pub_key()).is_none() {
-                let wallet = Wallet::new(self.pub_key(), self.name(), self.email(), INIT_BALANCE);
+                let wallet = Wallet::new(
+                    self.pub_key(),
+                    self.name(),
+                    self.email(),
+                    0,
+                    &Hash::zero(),
+                );
+                let wallet = schema.append_history(self.pub_key(), wallet, &tx_hash);
                 println!("Create the wallet: {:?}", wallet);
                 schema.wallets_mut().put(self.pub_key(), wallet);
                 Ok(())
@@ -167,6 +456,7 @@ pub mod contracts {
         }
 
         fn execute(&self, view: &mut Fork) -> ExecutionResult {
+            let tx_hash = self.hash();
             let mut schema = CurrencySchema::new(view);
 
             let sender = match schema.wallet(self.from()) {
@@ -180,9 +470,11 @@ pub mod contracts {
             };
 
             let amount = self.amount();
-            if sender.balance() >= amount {
-                let sender = sender.decrease(amount);
-                let receiver = receiver.increase(amount);
+            if schema.wallet_balance(self.from(), BASE_ASSET) >= amount {
+                schema.decrease_balance(self.from(), BASE_ASSET, amount);
+                schema.increase_balance(self.to(), BASE_ASSET, amount);
+                let sender = schema.append_history(self.from(), sender, &tx_hash);
+                let receiver = schema.append_history(self.to(), receiver, &tx_hash);
                 println!("Transfer between wallets: {:?} => {:?}", sender, receiver);
                 let mut wallets = schema.wallets_mut();
                 wallets.put(self.from(), sender);
@@ -194,46 +486,269 @@ pub mod contracts {
         }
     }
 
-    impl Transaction for TxFreeze {
+    impl Transaction for TxLock {
         fn verify(&self) -> bool {
-            self.verify_signature(self.pub_key())
+            self.verify_signature(self.from())
         }
 
         fn execute(&self, view: &mut Fork) -> ExecutionResult {
+            let tx_hash = self.hash();
             let mut schema = CurrencySchema::new(view);
 
-            let freezer = match schema.wallet(self.pub_key()) {
+            if schema.lock(self.hashlock()).is_some() {
+                Err(Error::LockAlreadyExists)?
+            }
+
+            let sender = match schema.wallet(self.from()) {
                 Some(val) => val,
                 None => Err(Error::SenderNotFound)?,
             };
 
             let amount = self.amount();
-            if freezer.balance() >= amount {
-                let freezer = freezer.freeze(amount);
-                println!("Hold {} tokens of wallet: {:?}", amount, freezer);
-                let mut wallets = schema.wallets_mut();
-                wallets.put(self.pub_key(), freezer);
+            if schema.wallet_balance(self.from(), BASE_ASSET) >= amount {
+                schema.decrease_balance(self.from(), BASE_ASSET, amount);
+                let sender = schema.append_history(self.from(), sender, &tx_hash);
+                println!("Lock {} tokens of wallet: {:?}", amount, sender);
+                schema.wallets_mut().put(self.from(), sender);
+
+                let lock = LockEntry::new(
+                    self.from(),
+                    self.to(),
+                    amount,
+                    self.hashlock(),
+                    self.deadline_height(),
+                );
+                schema.locks_mut().put(self.hashlock(), lock);
                 Ok(())
             } else {
                 Err(Error::InsufficientCurrencyAmount)?
             }
         }
     }
+
+    impl Transaction for TxClaim {
+        fn verify(&self) -> bool {
+            self.verify_signature(self.to())
+        }
+
+        fn execute(&self, view: &mut Fork) -> ExecutionResult {
+            let tx_hash = self.hash();
+            let height = CoreSchema::new(view.as_ref()).height();
+            let mut schema = CurrencySchema::new(view);
+
+            let lock = match schema.lock(self.hashlock()) {
+                Some(val) => val,
+                None => Err(Error::LockNotFound)?,
+            };
+
+            if height.0 >= lock.deadline_height() {
+                Err(Error::LockExpired)?
+            }
+
+            if *self.to() != *lock.to() {
+                Err(Error::ClaimantMismatch)?
+            }
+
+            if crypto::hash(self.preimage()) != *self.hashlock() {
+                Err(Error::InvalidPreimage)?
+            }
+
+            let claimer = match schema.wallet(self.to()) {
+                Some(val) => val,
+                None => Err(Error::ReceiverNotFound)?,
+            };
+
+            schema.increase_balance(self.to(), BASE_ASSET, lock.amount());
+            let claimer = schema.append_history(self.to(), claimer, &tx_hash);
+            println!("Claim lock {:?} to wallet: {:?}", self.hashlock(), claimer);
+            schema.wallets_mut().put(self.to(), claimer);
+            schema.locks_mut().remove(self.hashlock());
+            Ok(())
+        }
+    }
+
+    impl Transaction for TxRefund {
+        fn verify(&self) -> bool {
+            true
+        }
+
+        fn execute(&self, view: &mut Fork) -> ExecutionResult {
+            let tx_hash = self.hash();
+            let height = CoreSchema::new(view.as_ref()).height();
+            let mut schema = CurrencySchema::new(view);
+
+            let lock = match schema.lock(self.hashlock()) {
+                Some(val) => val,
+                None => Err(Error::LockNotFound)?,
+            };
+
+            if height.0 < lock.deadline_height() {
+                Err(Error::LockNotExpired)?
+            }
+
+            let refundee = match schema.wallet(lock.from()) {
+                Some(val) => val,
+                None => Err(Error::SenderNotFound)?,
+            };
+
+            schema.increase_balance(lock.from(), BASE_ASSET, lock.amount());
+            let refundee = schema.append_history(lock.from(), refundee, &tx_hash);
+            println!("Refund lock {:?} to wallet: {:?}", self.hashlock(), refundee);
+            schema.wallets_mut().put(lock.from(), refundee);
+            schema.locks_mut().remove(self.hashlock());
+            Ok(())
+        }
+    }
+
+    impl Transaction for TxIssue {
+        fn verify(&self) -> bool {
+            self.verify_signature(self.issuer())
+        }
+
+        fn execute(&self, view: &mut Fork) -> ExecutionResult {
+            let tx_hash = self.hash();
+            let mut schema = CurrencySchema::new(view);
+
+            let issuer_config = schema.issuer_config().get().expect(
+                "IssuerConfig not set; CurrencyService::initialize should have written it",
+            );
+            if *self.issuer() != *issuer_config.issuer_key() {
+                Err(Error::NotAuthorizedIssuer)?;
+            }
+
+            let wallet = match schema.wallet(self.pub_key()) {
+                Some(val) => val,
+                None => Err(Error::SenderNotFound)?,
+            };
+
+            schema.increase_balance(self.pub_key(), BASE_ASSET, self.amount());
+            let wallet = schema.append_history(self.pub_key(), wallet, &tx_hash);
+            println!("Issue {} tokens to wallet: {:?}", self.amount(), wallet);
+            schema.wallets_mut().put(self.pub_key(), wallet);
+            Ok(())
+        }
+    }
+
+    impl Transaction for TxTransferExchange {
+        fn verify(&self) -> bool {
+            (*self.from() != *self.to() || self.from_asset() != self.to_asset()) &&
+                self.verify_signature(self.from())
+        }
+
+        fn execute(&self, view: &mut Fork) -> ExecutionResult {
+            let tx_hash = self.hash();
+            let mut schema = CurrencySchema::new(view);
+
+            let sender = match schema.wallet(self.from()) {
+                Some(val) => val,
+                None => Err(Error::SenderNotFound)?,
+            };
+
+            let receiver = match schema.wallet(self.to()) {
+                Some(val) => val,
+                None => Err(Error::ReceiverNotFound)?,
+            };
+
+            let from_amount = self.from_amount();
+            if schema.wallet_balance(self.from(), self.from_asset()) < from_amount {
+                Err(Error::InsufficientCurrencyAmount)?
+            }
+
+            // `to_amount = from_amount * rate_ppm / 1_000_000`, computed in 128 bits so
+            // the multiplication can't silently wrap before the division brings it back
+            // into range.
+            let to_amount = (from_amount as u128)
+                .checked_mul(self.rate_ppm() as u128)
+                .map(|product| product / 1_000_000)
+                .filter(|&amount| amount <= u64::max_value() as u128)
+                .map(|amount| amount as u64)
+                .ok_or(Error::ConversionOverflow)?;
+
+            if to_amount == 0 {
+                Err(Error::ConversionOverflow)?
+            }
+
+            schema.decrease_balance(self.from(), self.from_asset(), from_amount);
+            schema.increase_balance(self.to(), self.to_asset(), to_amount);
+
+            let sender = schema.append_history(self.from(), sender, &tx_hash);
+            let receiver = schema.append_history(self.to(), receiver, &tx_hash);
+            println!(
+                "Exchange transfer: {:?} {} => {:?} {}",
+                sender,
+                self.from_asset(),
+                receiver,
+                self.to_asset()
+            );
+            let mut wallets = schema.wallets_mut();
+            wallets.put(self.from(), sender);
+            wallets.put(self.to(), receiver);
+            Ok(())
+        }
+    }
+
+    impl Transaction for TxFaucet {
+        fn verify(&self) -> bool {
+            self.verify_signature(self.pub_key())
+        }
+
+        fn execute(&self, view: &mut Fork) -> ExecutionResult {
+            let tx_hash = self.hash();
+            let mut schema = CurrencySchema::new(view);
+
+            let wallet = match schema.wallet(self.pub_key()) {
+                Some(val) => val,
+                None => Err(Error::SenderNotFound)?,
+            };
+
+            let config = schema.faucet_config().get().expect(
+                "Faucet config was not initialized by the service",
+            );
+            let ceiling = config.ceiling();
+
+            let amount = self.amount();
+            let already_withdrawn = schema.faucet_withdrawn(self.pub_key());
+            let total_withdrawn = already_withdrawn
+                .checked_add(amount)
+                .ok_or(Error::FaucetLimitExceeded)?;
+            if total_withdrawn > ceiling {
+                Err(Error::FaucetLimitExceeded)?
+            }
+
+            schema.increase_balance(self.pub_key(), BASE_ASSET, amount);
+            schema.record_faucet_withdrawal(self.pub_key(), amount);
+            let wallet = schema.append_history(self.pub_key(), wallet, &tx_hash);
+            println!("Faucet paid out {} tokens to wallet: {:?}", amount, wallet);
+            schema.wallets_mut().put(self.pub_key(), wallet);
+            Ok(())
+        }
+    }
 }
 
 pub mod api {
-    use exonum::blockchain::{Blockchain, Transaction};
+    use std::collections::BTreeMap;
+
+    use exonum::blockchain::{Blockchain, Block, Transaction};
     use exonum::encoding::serialize::FromHex;
     use exonum::node::{ApiSender, TransactionSend};
-    use exonum::crypto::{Hash, PublicKey};
+    use exonum::crypto::{Hash, PublicKey, SecretKey};
+    use exonum::storage::{ListProof, MapProof};
     use exonum::api::{Api, ApiError};
     use iron::prelude::*;
     use iron::{headers::ContentType, modifiers::Header, status::Status};
     use router::Router;
 
+    use chacha20poly1305::aead::{Aead, NewAead};
+    use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+    use hmac::Hmac;
+    use pbkdf2::pbkdf2;
+    use rand::rngs::OsRng;
+    use rand::RngCore;
+    use sha2::Sha256;
+
     use bodyparser;
     use serde_json;
-    use schema::{CurrencySchema, Wallet};
+    use schema::{balance_key, CurrencySchema, Wallet, BASE_ASSET};
     use transactions::CurrencyTransactions;
 
     #[derive(Clone)]
@@ -258,6 +773,108 @@ pub mod api {
         pub tx_hash: Hash,
     }
 
+    #[derive(Serialize, Deserialize)]
+    pub struct WalletProof {
+        // Header of the latest committed block.
+        pub block_header: Block,
+        // Proof of existence (or absence) of the wallet in the `wallets` table.
+        pub to_wallet: MapProof<PublicKey, Wallet>,
+        // The wallet itself, if it was found.
+        pub wallet: Option<Wallet>,
+        // Asset id the balance proof below is for; always `BASE_ASSET` today.
+        pub asset_id: String,
+        // Proof of the wallet's `asset_id` balance in the `wallet_balances` table,
+        // so a client can verify a quoted balance against `state_hash` without
+        // trusting the node.
+        pub to_balance: MapProof<Hash, u64>,
+        // The balance itself, in base units of `asset_id`.
+        pub balance: u64,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct WalletInfo {
+        // The wallet's fixed fields (name, email, history metadata).
+        pub wallet: Wallet,
+        // Per-asset balances, keyed by asset id.
+        pub balances: BTreeMap<String, u64>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct WalletHistoryProof {
+        // Ordered list of transaction hashes that touched the wallet.
+        pub transactions: Vec<Hash>,
+        // Proof that `transactions` matches the wallet's `history_hash`.
+        pub proof: ListProof<Hash>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct BackupExportRequest {
+        // Secret key to seal into the backup envelope.
+        pub secret_key: SecretKey,
+        // Passphrase the envelope is sealed with; never stored on-chain.
+        pub passphrase: String,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct BackupEnvelope {
+        // Fresh 16-byte salt generated for this export; feeds PBKDF2 alongside
+        // the passphrase to derive the encryption key.
+        pub salt: Vec<u8>,
+        // Fresh 12-byte ChaCha20-Poly1305 nonce generated for this export.
+        pub nonce: Vec<u8>,
+        // `secret_key`, sealed under a key derived from the passphrase.
+        pub ciphertext: Vec<u8>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct BackupImportRequest {
+        // Passphrase the envelope was sealed with.
+        pub passphrase: String,
+        // Salt the envelope was derived with.
+        pub salt: Vec<u8>,
+        // Nonce the envelope was sealed under.
+        pub nonce: Vec<u8>,
+        // Sealed secret key, as returned from `/v1/backup/export`.
+        pub ciphertext: Vec<u8>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct BackupImportResponse {
+        pub public_key: PublicKey,
+        pub secret_key: SecretKey,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct FaucetAllowance {
+        // Cumulative withdrawal ceiling, in base units.
+        pub limit: u64,
+        // Amount already withdrawn, in base units.
+        pub withdrawn: u64,
+        // Remaining allowance, in base units.
+        pub remaining: u64,
+    }
+
+    // PBKDF2 work factor for backup key derivation. Chosen as a baseline cost
+    // for an interactively-entered passphrase; revisit if this becomes a
+    // measured bottleneck.
+    const BACKUP_KDF_ROUNDS: u32 = 100_000;
+
+    // Derives a ChaCha20-Poly1305 key from a user-chosen passphrase and a
+    // per-export salt via PBKDF2-HMAC-SHA256, so the key can't be recovered
+    // from a single fast hash and the same passphrase never reuses a key.
+    fn derive_backup_key(passphrase: &str, salt: &[u8]) -> Key {
+        let mut derived = [0u8; 32];
+        pbkdf2::<Hmac<Sha256>>(passphrase.as_bytes(), salt, BACKUP_KDF_ROUNDS, &mut derived);
+        *Key::from_slice(&derived)
+    }
+
+    // Generates a fresh random salt for a backup export.
+    fn generate_salt() -> Vec<u8> {
+        let mut salt = vec![0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        salt
+    }
+
     impl CryptocurrencyApi {
         fn post_transaction(&self, req: &mut Request) -> IronResult<Response> {
             match req.get::<bodyparser::Struct<CurrencyTransactions>>() {
@@ -293,7 +910,16 @@ pub mod api {
             let schema = CurrencySchema::new(snapshot);
 
             if let Some(wallet) = schema.wallet(&public_key) {
-                self.ok_response(&serde_json::to_value(wallet).unwrap())
+                let balances = schema
+                    .wallet_assets(&public_key)
+                    .iter()
+                    .map(|asset_id| {
+                        let balance = schema.wallet_balance(&public_key, &asset_id);
+                        (asset_id, balance)
+                    })
+                    .collect();
+                let info = WalletInfo { wallet, balances };
+                self.ok_response(&serde_json::to_value(&info).unwrap())
             } else {
                 self.not_found_response(
                     &serde_json::to_value("Wallet not found").unwrap()
@@ -301,6 +927,69 @@ pub mod api {
             }
         }
 
+        fn get_wallet_proof(&self, req: &mut Request) -> IronResult<Response> {
+            let path = req.url.path();
+            let wallet_key = path.last().unwrap();
+            let public_key = PublicKey::from_hex(wallet_key).map_err(|e| {
+                IronError::new(
+                    e,
+                    (
+                        Status::BadRequest,
+                        Header(ContentType::json()),
+                        "\"Invalid request param: `pub_key`\"",
+                    ),
+                )
+            })?;
+
+            let snapshot = self.blockchain.snapshot();
+            let schema = CurrencySchema::new(&snapshot);
+
+            let to_wallet = schema.wallets().get_proof(public_key);
+            let wallet = schema.wallet(&public_key);
+            let block_header = self.blockchain.last_block();
+
+            let asset_id = BASE_ASSET.to_owned();
+            let to_balance = schema
+                .wallet_balances()
+                .get_proof(balance_key(&public_key, BASE_ASSET));
+            let balance = schema.wallet_balance(&public_key, BASE_ASSET);
+
+            let proof = WalletProof {
+                block_header,
+                to_wallet,
+                wallet,
+                asset_id,
+                to_balance,
+                balance,
+            };
+            self.ok_response(&serde_json::to_value(&proof).unwrap())
+        }
+
+        fn get_wallet_history(&self, req: &mut Request) -> IronResult<Response> {
+            let path = req.url.path();
+            let wallet_key = path.last().unwrap();
+            let public_key = PublicKey::from_hex(wallet_key).map_err(|e| {
+                IronError::new(
+                    e,
+                    (
+                        Status::BadRequest,
+                        Header(ContentType::json()),
+                        "\"Invalid request param: `pub_key`\"",
+                    ),
+                )
+            })?;
+
+            let snapshot = self.blockchain.snapshot();
+            let schema = CurrencySchema::new(&snapshot);
+
+            let history = schema.wallet_history(&public_key);
+            let transactions: Vec<Hash> = history.iter().collect();
+            let proof = history.get_range_proof(0, history.len());
+
+            let history_proof = WalletHistoryProof { transactions, proof };
+            self.ok_response(&serde_json::to_value(&history_proof).unwrap())
+        }
+
         fn get_wallets(&self, _: &mut Request) -> IronResult<Response> {
             let snapshot = self.blockchain.snapshot();
             let schema = CurrencySchema::new(snapshot);
@@ -309,6 +998,120 @@ pub mod api {
 
             self.ok_response(&serde_json::to_value(&wallets).unwrap())
         }
+
+        fn get_lock(&self, req: &mut Request) -> IronResult<Response> {
+            let path = req.url.path();
+            let hashlock_param = path.last().unwrap();
+            let hashlock = Hash::from_hex(hashlock_param).map_err(|e| {
+                IronError::new(
+                    e,
+                    (
+                        Status::BadRequest,
+                        Header(ContentType::json()),
+                        "\"Invalid request param: `hashlock`\"",
+                    ),
+                )
+            })?;
+
+            let snapshot = self.blockchain.snapshot();
+            let schema = CurrencySchema::new(snapshot);
+
+            if let Some(lock) = schema.lock(&hashlock) {
+                self.ok_response(&serde_json::to_value(lock).unwrap())
+            } else {
+                self.not_found_response(
+                    &serde_json::to_value("Lock not found").unwrap()
+                )
+            }
+        }
+
+        fn export_backup(&self, req: &mut Request) -> IronResult<Response> {
+            match req.get::<bodyparser::Struct<BackupExportRequest>>() {
+                Ok(Some(body)) => {
+                    let salt = generate_salt();
+                    let key = derive_backup_key(&body.passphrase, &salt);
+                    let cipher = ChaCha20Poly1305::new(&key);
+
+                    let mut nonce_bytes = [0u8; 12];
+                    OsRng.fill_bytes(&mut nonce_bytes);
+                    let nonce = Nonce::from_slice(&nonce_bytes);
+
+                    let ciphertext = cipher
+                        .encrypt(nonce, body.secret_key.as_ref())
+                        .map_err(|_| ApiError::BadRequest("Failed to seal backup".into()))?;
+
+                    let envelope = BackupEnvelope {
+                        salt,
+                        nonce: nonce_bytes.to_vec(),
+                        ciphertext,
+                    };
+                    self.ok_response(&serde_json::to_value(&envelope).unwrap())
+                }
+                Ok(None) => Err(ApiError::BadRequest("Empty request body".into()))?,
+                Err(e) => Err(ApiError::BadRequest(e.to_string()))?,
+            }
+        }
+
+        fn import_backup(&self, req: &mut Request) -> IronResult<Response> {
+            match req.get::<bodyparser::Struct<BackupImportRequest>>() {
+                Ok(Some(body)) => {
+                    let key = derive_backup_key(&body.passphrase, &body.salt);
+                    let cipher = ChaCha20Poly1305::new(&key);
+                    let nonce = Nonce::from_slice(&body.nonce);
+
+                    let plaintext = cipher
+                        .decrypt(nonce, body.ciphertext.as_ref())
+                        .map_err(|_| {
+                            ApiError::BadRequest("Failed to unseal backup".into())
+                        })?;
+
+                    let secret_key = SecretKey::from_slice(&plaintext).ok_or_else(|| {
+                        ApiError::BadRequest("Backup did not contain a valid secret key".into())
+                    })?;
+                    // Ed25519 secret keys embed their matching public key in the
+                    // second half, so it doesn't need to be stored in the envelope.
+                    let public_key = PublicKey::from_slice(&plaintext[32..]).ok_or_else(|| {
+                        ApiError::BadRequest("Backup did not contain a valid secret key".into())
+                    })?;
+
+                    let response = BackupImportResponse {
+                        public_key,
+                        secret_key,
+                    };
+                    self.ok_response(&serde_json::to_value(&response).unwrap())
+                }
+                Ok(None) => Err(ApiError::BadRequest("Empty request body".into()))?,
+                Err(e) => Err(ApiError::BadRequest(e.to_string()))?,
+            }
+        }
+
+        fn get_faucet_allowance(&self, req: &mut Request) -> IronResult<Response> {
+            let path = req.url.path();
+            let wallet_key = path.last().unwrap();
+            let public_key = PublicKey::from_hex(wallet_key).map_err(|e| {
+                IronError::new(
+                    e,
+                    (
+                        Status::BadRequest,
+                        Header(ContentType::json()),
+                        "\"Invalid request param: `pub_key`\"",
+                    ),
+                )
+            })?;
+
+            let snapshot = self.blockchain.snapshot();
+            let schema = CurrencySchema::new(snapshot);
+
+            let config = schema.faucet_config().get().expect(
+                "Faucet config was not initialized by the service",
+            );
+            let limit = config.ceiling();
+            let withdrawn = schema.faucet_withdrawn(&public_key);
+            let remaining = limit.saturating_sub(withdrawn);
+
+            let allowance = FaucetAllowance { limit, withdrawn, remaining };
+            self.ok_response(&serde_json::to_value(&allowance).unwrap())
+        }
     }
 
     impl Api for CryptocurrencyApi {
@@ -318,42 +1121,110 @@ pub mod api {
             let self_ = self.clone();
             let post_transfer = move |req: &mut Request| self_.post_transaction(req);
             let self_ = self.clone();
-            let post_freeze = move |req: &mut Request| self_.post_transaction(req);
+            let post_issue = move |req: &mut Request| self_.post_transaction(req);
+            let self_ = self.clone();
+            let post_transfer_exchange = move |req: &mut Request| self_.post_transaction(req);
+            let self_ = self.clone();
+            let post_lock = move |req: &mut Request| self_.post_transaction(req);
+            let self_ = self.clone();
+            let post_claim = move |req: &mut Request| self_.post_transaction(req);
+            let self_ = self.clone();
+            let post_refund = move |req: &mut Request| self_.post_transaction(req);
             let self_ = self.clone();
             let get_wallets = move |req: &mut Request| self_.get_wallets(req);
             let self_ = self.clone();
             let get_wallet = move |req: &mut Request| self_.get_wallet(req);
+            let self_ = self.clone();
+            let get_wallet_proof = move |req: &mut Request| self_.get_wallet_proof(req);
+            let self_ = self.clone();
+            let get_wallet_history = move |req: &mut Request| self_.get_wallet_history(req);
+            let self_ = self.clone();
+            let get_lock = move |req: &mut Request| self_.get_lock(req);
+            let self_ = self.clone();
+            let post_backup_export = move |req: &mut Request| self_.export_backup(req);
+            let self_ = self.clone();
+            let post_backup_import = move |req: &mut Request| self_.import_backup(req);
+            let self_ = self.clone();
+            let post_faucet = move |req: &mut Request| self_.post_transaction(req);
+            let self_ = self.clone();
+            let get_faucet_allowance = move |req: &mut Request| self_.get_faucet_allowance(req);
 
             // Bind handlers to specific routes.
             router.post("/v1/wallets", post_create_wallet, "post_create_wallet");
             router.post("/v1/wallets/transfer", post_transfer, "post_transfer");
-            router.post("/v1/wallets/freeze", post_freeze, "post_freeze");
+            router.post("/v1/wallets/issue", post_issue, "post_issue");
+            router.post(
+                "/v1/wallets/transfer_exchange",
+                post_transfer_exchange,
+                "post_transfer_exchange",
+            );
+            router.post("/v1/wallets/faucet", post_faucet, "post_faucet");
+            router.post("/v1/locks/lock", post_lock, "post_lock");
+            router.post("/v1/locks/claim", post_claim, "post_claim");
+            router.post("/v1/locks/refund", post_refund, "post_refund");
             router.get("/v1/wallets", get_wallets, "get_wallets");
             router.get("/v1/wallet/:pub_key", get_wallet, "get_wallet");
+            router.get(
+                "/v1/wallets/:pub_key/proof",
+                get_wallet_proof,
+                "get_wallet_proof",
+            );
+            router.get(
+                "/v1/wallets/:pub_key/history",
+                get_wallet_history,
+                "get_wallet_history",
+            );
+            router.get("/v1/lock/:hashlock", get_lock, "get_lock");
+            router.post("/v1/backup/export", post_backup_export, "post_backup_export");
+            router.post("/v1/backup/import", post_backup_import, "post_backup_import");
+            router.get(
+                "/v1/wallets/:pub_key/faucet",
+                get_faucet_allowance,
+                "get_faucet_allowance",
+            );
         }
     }
 }
 
 pub mod service {
     use exonum::blockchain::{ApiContext, Service, Transaction, TransactionSet};
-    use exonum::{encoding, api::Api, crypto::Hash, messages::RawTransaction, storage::Snapshot};
+    use exonum::{
+        encoding, api::Api, crypto::{Hash, PublicKey}, messages::RawTransaction,
+        storage::{Fork, Snapshot},
+    };
     use iron::Handler;
     use router::Router;
+    use serde_json::Value;
 
     use transactions::CurrencyTransactions;
+    use schema::{CurrencySchema, FaucetConfig, IssuerConfig};
     use api::CryptocurrencyApi;
 
     pub const SERVICE_ID: u16 = 1;
 
-    pub struct CurrencyService;
+    pub struct CurrencyService {
+        faucet_limit: u64,
+        denomination: u8,
+        issuer_key: PublicKey,
+    }
+
+    impl CurrencyService {
+        // `faucet_limit` is expressed in whole tokens; `denomination` is the
+        // number of decimal places a whole token is split into base units.
+        // `issuer_key` is the only key `TxIssue` will accept as the minter.
+        pub fn new(faucet_limit: u64, denomination: u8, issuer_key: PublicKey) -> Self {
+            CurrencyService { faucet_limit, denomination, issuer_key }
+        }
+    }
 
     impl Service for CurrencyService {
         fn service_id(&self) -> u16 { SERVICE_ID }
 
         fn service_name(&self) -> &'static str { "cryptocurrency" }
 
-        fn state_hash(&self, _: &Snapshot) -> Vec<Hash> {
-            vec![]
+        fn state_hash(&self, snapshot: &Snapshot) -> Vec<Hash> {
+            let schema = CurrencySchema::new(snapshot);
+            schema.state_hash()
         }
 
         fn tx_from_raw(&self, raw: RawTransaction) -> Result<Box<Transaction>, encoding::Error> {
@@ -361,6 +1232,14 @@ pub mod service {
             Ok(tx.into())
         }
 
+        fn initialize(&self, fork: &mut Fork) -> Value {
+            let mut schema = CurrencySchema::new(fork);
+            let config = FaucetConfig::new(self.faucet_limit, self.denomination);
+            schema.set_faucet_config(config);
+            schema.set_issuer_config(IssuerConfig::new(&self.issuer_key));
+            Value::Null
+        }
+
         fn public_api_handler(&self, ctx: &ApiContext) -> Option<Box<Handler>> {
             let mut router = Router::new();
             let api = CryptocurrencyApi::new(
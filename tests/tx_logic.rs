@@ -4,24 +4,35 @@ extern crate eden_exonum as cryptocurrency;
 
 use exonum::blockchain::Transaction;
 use exonum::crypto::{self, PublicKey, SecretKey};
-use exonum_testkit::{TestKit, TestKitBuilder};
+use exonum_testkit::{ApiKind, TestKit, TestKitBuilder};
 // Import datatypes used in tests from the crate where the service is defined.
+use cryptocurrency::api::{BackupEnvelope, BackupExportRequest, BackupImportRequest, BackupImportResponse};
 use cryptocurrency::schema::{CurrencySchema, Wallet};
-use cryptocurrency::transactions::{TxCreateWallet, TxTransfer};
+use cryptocurrency::transactions::{
+    TxCreateWallet, TxTransfer, TxIssue, TxLock, TxClaim, TxRefund, TxTransferExchange, TxFaucet,
+};
 use cryptocurrency::service::CurrencyService;
 
-fn init_testkit() -> TestKit {
+fn create_wallet(pubkey: &PublicKey, name: &str, key: &SecretKey) -> TxCreateWallet {
+    TxCreateWallet::new(pubkey, name, &format!("{}@example.com", name.to_lowercase()), key)
+}
+
+fn init_testkit(issuer_key: &PublicKey) -> TestKit {
     TestKitBuilder::validator()
-        .with_service(CurrencyService)
+        .with_service(CurrencyService::new(5, 2, *issuer_key))
         .create()
 }
 
 #[test]
 fn test_create_wallet() {
-    let mut testkit = init_testkit();
+    let (issuer_pubkey, issuer_key) = crypto::gen_keypair();
+    let mut testkit = init_testkit(&issuer_pubkey);
     let (pubkey, key) = crypto::gen_keypair();
     testkit.create_block_with_transactions(txvec![
-        TxCreateWallet::new(&pubkey, "Alice", &key),
+        create_wallet(&pubkey, "Alice", &key),
+    ]);
+    testkit.create_block_with_transactions(txvec![
+        TxIssue::new(&pubkey, &issuer_pubkey, 100, 0, &issuer_key),
     ]);
     let wallet = {
         let snapshot = testkit.snapshot();
@@ -31,5 +42,273 @@ fn test_create_wallet() {
     };
     assert_eq!(*wallet.pub_key(), pubkey);
     assert_eq!(wallet.name(), "Alice");
-    assert_eq!(wallet.balance(), 100);
+
+    let balance = {
+        let snapshot = testkit.snapshot();
+        CurrencySchema::new(&snapshot).wallet_balance(&pubkey, cryptocurrency::schema::BASE_ASSET)
+    };
+    assert_eq!(balance, 100);
+}
+
+#[test]
+fn test_issue_requires_issuer_key() {
+    let (issuer_pubkey, _issuer_key) = crypto::gen_keypair();
+    let mut testkit = init_testkit(&issuer_pubkey);
+    let (pubkey, key) = crypto::gen_keypair();
+    testkit.create_block_with_transactions(txvec![
+        create_wallet(&pubkey, "Alice", &key),
+    ]);
+
+    // Mallory names herself as issuer and signs with her own key, so
+    // verify()'s signature check passes; execute() must still reject her
+    // because she isn't the service's configured issuer.
+    let (mallory_pubkey, mallory_key) = crypto::gen_keypair();
+    testkit.create_block_with_transactions(txvec![
+        TxIssue::new(&pubkey, &mallory_pubkey, 100, 0, &mallory_key),
+    ]);
+
+    let balance = {
+        let snapshot = testkit.snapshot();
+        CurrencySchema::new(&snapshot).wallet_balance(&pubkey, cryptocurrency::schema::BASE_ASSET)
+    };
+    assert_eq!(balance, 0);
+}
+
+#[test]
+fn test_wallet_proof() {
+    let (issuer_pubkey, _issuer_key) = crypto::gen_keypair();
+    let mut testkit = init_testkit(&issuer_pubkey);
+    let (pubkey, key) = crypto::gen_keypair();
+    testkit.create_block_with_transactions(txvec![
+        create_wallet(&pubkey, "Alice", &key),
+    ]);
+
+    let snapshot = testkit.snapshot();
+    let schema = CurrencySchema::new(&snapshot);
+    let wallet = schema.wallet(&pubkey).expect("No wallet persisted");
+
+    // The wallet must be provable against `wallets`' own merkle root, and that
+    // root must be one of the hashes folded into `Service::state_hash`.
+    let proof = schema.wallets().get_proof(pubkey);
+    let checked = proof.check().expect("wallet proof must validate");
+    assert_eq!(checked.merkle_root(), schema.wallets().merkle_root());
+    assert!(schema.state_hash().contains(&schema.wallets().merkle_root()));
+    assert_eq!(*wallet.pub_key(), pubkey);
+}
+
+#[test]
+fn test_wallet_history() {
+    let (issuer_pubkey, issuer_key) = crypto::gen_keypair();
+    let mut testkit = init_testkit(&issuer_pubkey);
+    let (pubkey, key) = crypto::gen_keypair();
+    testkit.create_block_with_transactions(txvec![
+        create_wallet(&pubkey, "Alice", &key),
+    ]);
+    let issue_tx = TxIssue::new(&pubkey, &issuer_pubkey, 100, 0, &issuer_key);
+    testkit.create_block_with_transactions(txvec![issue_tx.clone()]);
+
+    let snapshot = testkit.snapshot();
+    let schema = CurrencySchema::new(&snapshot);
+    let history = schema.wallet_history(&pubkey);
+
+    // Both the wallet creation and the issuance should have appended to the
+    // wallet's history, and the wallet's stored history_hash/history_len
+    // must match the list they were pushed onto.
+    assert_eq!(history.len(), 2);
+    assert_eq!(history.get(1), Some(issue_tx.hash()));
+
+    let wallet = schema.wallet(&pubkey).expect("No wallet persisted");
+    assert_eq!(wallet.history_len(), 2);
+    assert_eq!(*wallet.history_hash(), history.merkle_root());
+}
+
+#[test]
+fn test_htlc_lock_claim_and_refund() {
+    let (issuer_pubkey, issuer_key) = crypto::gen_keypair();
+    let mut testkit = init_testkit(&issuer_pubkey);
+    let (alice_pubkey, alice_key) = crypto::gen_keypair();
+    let (bob_pubkey, bob_key) = crypto::gen_keypair();
+    let (mallory_pubkey, mallory_key) = crypto::gen_keypair();
+
+    testkit.create_block_with_transactions(txvec![
+        create_wallet(&alice_pubkey, "Alice", &alice_key),
+        create_wallet(&bob_pubkey, "Bob", &bob_key),
+        create_wallet(&mallory_pubkey, "Mallory", &mallory_key),
+    ]);
+    testkit.create_block_with_transactions(txvec![
+        TxIssue::new(&alice_pubkey, &issuer_pubkey, 100, 0, &issuer_key),
+    ]);
+
+    let preimage = b"s3cr3t".to_vec();
+    let hashlock = crypto::hash(&preimage);
+    testkit.create_block_with_transactions(txvec![
+        TxLock::new(&alice_pubkey, &bob_pubkey, 40, &hashlock, 1000, 0, &alice_key),
+    ]);
+
+    let base_asset = cryptocurrency::schema::BASE_ASSET;
+    let alice_balance = |testkit: &TestKit| {
+        let snapshot = testkit.snapshot();
+        CurrencySchema::new(&snapshot).wallet_balance(&alice_pubkey, base_asset)
+    };
+    assert_eq!(alice_balance(&testkit), 60);
+
+    // Mallory isn't the lock's intended recipient and must be rejected even
+    // though she knows the preimage.
+    testkit.create_block_with_transactions(txvec![
+        TxClaim::new(&mallory_pubkey, &hashlock, &preimage, &mallory_key),
+    ]);
+    let mallory_balance = {
+        let snapshot = testkit.snapshot();
+        CurrencySchema::new(&snapshot).wallet_balance(&mallory_pubkey, base_asset)
+    };
+    assert_eq!(mallory_balance, 0);
+
+    // Bob is the intended recipient and successfully claims the lock.
+    testkit.create_block_with_transactions(txvec![
+        TxClaim::new(&bob_pubkey, &hashlock, &preimage, &bob_key),
+    ]);
+    let bob_balance = {
+        let snapshot = testkit.snapshot();
+        CurrencySchema::new(&snapshot).wallet_balance(&bob_pubkey, base_asset)
+    };
+    assert_eq!(bob_balance, 40);
+
+    // A lock nobody claims before its deadline can be refunded to the sender.
+    let refund_preimage = b"unused".to_vec();
+    let refund_hashlock = crypto::hash(&refund_preimage);
+    testkit.create_block_with_transactions(txvec![
+        TxLock::new(&alice_pubkey, &bob_pubkey, 20, &refund_hashlock, 0, 1, &alice_key),
+    ]);
+    testkit.create_block_with_transactions(txvec![
+        TxRefund::new(&refund_hashlock, &alice_key),
+    ]);
+    assert_eq!(alice_balance(&testkit), 60);
+}
+
+#[test]
+fn test_exchange_transfer_and_overflow() {
+    let (issuer_pubkey, issuer_key) = crypto::gen_keypair();
+    let mut testkit = init_testkit(&issuer_pubkey);
+    let (alice_pubkey, alice_key) = crypto::gen_keypair();
+    let (bob_pubkey, bob_key) = crypto::gen_keypair();
+    let base_asset = cryptocurrency::schema::BASE_ASSET;
+
+    testkit.create_block_with_transactions(txvec![
+        create_wallet(&alice_pubkey, "Alice", &alice_key),
+        create_wallet(&bob_pubkey, "Bob", &bob_key),
+    ]);
+    testkit.create_block_with_transactions(txvec![
+        TxIssue::new(&alice_pubkey, &issuer_pubkey, 1_000, 0, &issuer_key),
+    ]);
+
+    // 200 EXO at a rate of 500_000 ppm (0.5) converts to 100 USD.
+    testkit.create_block_with_transactions(txvec![
+        TxTransferExchange::new(
+            &alice_pubkey,
+            &bob_pubkey,
+            base_asset,
+            "USD",
+            200,
+            500_000,
+            0,
+            &alice_key,
+        ),
+    ]);
+
+    let balances = |testkit: &TestKit| {
+        let snapshot = testkit.snapshot();
+        let schema = CurrencySchema::new(&snapshot);
+        (
+            schema.wallet_balance(&alice_pubkey, base_asset),
+            schema.wallet_balance(&bob_pubkey, "USD"),
+        )
+    };
+    assert_eq!(balances(&testkit), (800, 100));
+
+    // A conversion whose result would not fit in a u64 must be rejected
+    // rather than silently wrapping into a bogus credit.
+    let (rich_pubkey, rich_key) = crypto::gen_keypair();
+    testkit.create_block_with_transactions(txvec![
+        create_wallet(&rich_pubkey, "Rich", &rich_key),
+    ]);
+    testkit.create_block_with_transactions(txvec![
+        TxIssue::new(&rich_pubkey, &issuer_pubkey, u64::max_value(), 1, &issuer_key),
+    ]);
+    testkit.create_block_with_transactions(txvec![
+        TxTransferExchange::new(
+            &rich_pubkey,
+            &bob_pubkey,
+            base_asset,
+            "USD",
+            u64::max_value(),
+            u64::max_value(),
+            0,
+            &rich_key,
+        ),
+    ]);
+
+    let snapshot = testkit.snapshot();
+    let schema = CurrencySchema::new(&snapshot);
+    assert_eq!(schema.wallet_balance(&rich_pubkey, base_asset), u64::max_value());
+    assert_eq!(schema.wallet_balance(&bob_pubkey, "USD"), 100);
+}
+
+#[test]
+fn test_backup_export_import_roundtrip() {
+    let (issuer_pubkey, _issuer_key) = crypto::gen_keypair();
+    let testkit = init_testkit(&issuer_pubkey);
+    let api = testkit.api();
+
+    let (public_key, secret_key) = crypto::gen_keypair();
+    let export_request = BackupExportRequest {
+        secret_key: secret_key.clone(),
+        passphrase: "correct horse battery staple".to_owned(),
+    };
+    let envelope: BackupEnvelope = api.public(ApiKind::Service("cryptocurrency"))
+        .query(&export_request)
+        .post("v1/backup/export")
+        .unwrap();
+
+    let import_request = BackupImportRequest {
+        passphrase: "correct horse battery staple".to_owned(),
+        salt: envelope.salt,
+        nonce: envelope.nonce,
+        ciphertext: envelope.ciphertext,
+    };
+    let response: BackupImportResponse = api.public(ApiKind::Service("cryptocurrency"))
+        .query(&import_request)
+        .post("v1/backup/import")
+        .unwrap();
+
+    assert_eq!(response.public_key, public_key);
+    assert_eq!(response.secret_key, secret_key);
+}
+
+#[test]
+fn test_faucet_ceiling_enforced() {
+    let (issuer_pubkey, _issuer_key) = crypto::gen_keypair();
+    // init_testkit configures a faucet limit of 5 whole tokens at 2 decimal
+    // places, i.e. a 500 base-unit ceiling per wallet.
+    let mut testkit = init_testkit(&issuer_pubkey);
+    let (pubkey, key) = crypto::gen_keypair();
+    let base_asset = cryptocurrency::schema::BASE_ASSET;
+    testkit.create_block_with_transactions(txvec![
+        create_wallet(&pubkey, "Alice", &key),
+    ]);
+
+    testkit.create_block_with_transactions(txvec![
+        TxFaucet::new(&pubkey, 500, 0, &key),
+    ]);
+    let balance = |testkit: &TestKit| {
+        let snapshot = testkit.snapshot();
+        CurrencySchema::new(&snapshot).wallet_balance(&pubkey, base_asset)
+    };
+    assert_eq!(balance(&testkit), 500);
+
+    // Any further withdrawal would exceed the wallet's cumulative ceiling
+    // and must be rejected, leaving the balance unchanged.
+    testkit.create_block_with_transactions(txvec![
+        TxFaucet::new(&pubkey, 1, 1, &key),
+    ]);
+    assert_eq!(balance(&testkit), 500);
 }
\ No newline at end of file